@@ -4,11 +4,10 @@ use anyhow::{Context, Result};
 use pnet::packet::{ip::IpNextHeaderProtocols, Packet};
 use pnet::transport::{self, TransportChannelType, TransportProtocol, TransportSender};
 use pnet::util;
-use std::cmp::Reverse;
-use std::collections::{BinaryHeap, VecDeque};
+use std::collections::VecDeque;
 use std::fmt::{self, Display};
 use std::net::{IpAddr, Ipv4Addr};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 const SOCKET_BUFFER_SIZE: usize = 4380;
 
@@ -26,8 +25,106 @@ pub struct Socket {
     pub sender: TransportSender,
     pub connected_connection_queue: VecDeque<SockID>,
     pub listening_socket: Option<SockID>,
-    pub recv_buffer: Vec<u8>,
+    pub recv_buffer: SocketBuffer,
+    pub send_buffer: SocketBuffer,
     pub retransmission_queue: VecDeque<RetransmissionQueueEntry>,
+    pub keep_alive: KeepAlive,
+}
+
+/// アイドルな `Established` 接続の生存確認。無効時は `interval` が `None`。
+#[derive(Clone, Debug, Default)]
+pub struct KeepAlive {
+    /// プローブを送るまでのアイドル時間。`None` なら keep-alive 無効。
+    pub interval: Option<Duration>,
+    /// 次にプローブを送る時刻。受信のたびに先送りする。
+    pub next_deadline: Option<SystemTime>,
+    /// ACK が返らないまま送り続けたプローブ数。
+    pub probes_sent: u8,
+}
+
+/// これを超えて無応答なら接続を切る。
+pub const MAX_KEEPALIVE_PROBES: u8 = 3;
+
+impl KeepAlive {
+    /// 受信のたびに呼ぶ。プローブ数をリセットし、次の期限を先送りする。
+    pub fn on_segment_received(&mut self) {
+        self.probes_sent = 0;
+        self.refresh_deadline();
+    }
+
+    /// 現在時刻を基準に次の期限を張り直す。interval 無効なら期限を `None` にする。
+    pub fn refresh_deadline(&mut self) {
+        self.next_deadline = self.interval.map(|interval| SystemTime::now() + interval);
+    }
+
+    /// 期限を過ぎていればプローブを送るべき。
+    pub fn should_probe(&self) -> bool {
+        match self.next_deadline {
+            Some(deadline) => SystemTime::now() >= deadline,
+            None => false,
+        }
+    }
+}
+
+/// 単一のアロケーションを使い回すリングバッファ。
+/// `read_at` から `length` バイトが有効なデータで、末尾は容量で折り返す。
+#[derive(Clone, Debug)]
+pub struct SocketBuffer {
+    storage: Vec<u8>,
+    read_at: usize,
+    length: usize,
+}
+
+impl SocketBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            storage: vec![0; capacity],
+            read_at: 0,
+            length: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.storage.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// 受信側が次に受け取れる空き容量（広告ウィンドウ）。
+    pub fn window(&self) -> usize {
+        self.capacity() - self.length
+    }
+
+    /// 末尾に `data` を詰められるだけ詰め、実際に書き込んだバイト数を返す。
+    pub fn enqueue_slice(&mut self, data: &[u8]) -> usize {
+        let to_write = data.len().min(self.window());
+        let capacity = self.capacity();
+        let mut offset = (self.read_at + self.length) % capacity;
+        for &byte in &data[..to_write] {
+            self.storage[offset] = byte;
+            offset = (offset + 1) % capacity;
+        }
+        self.length += to_write;
+        to_write
+    }
+
+    /// 先頭から `data` に取り出し、`read_at` を進めて読み出した分だけ返す。
+    pub fn dequeue_slice(&mut self, data: &mut [u8]) -> usize {
+        let to_read = data.len().min(self.length);
+        let capacity = self.capacity();
+        for slot in data.iter_mut().take(to_read) {
+            *slot = self.storage[self.read_at];
+            self.read_at = (self.read_at + 1) % capacity;
+        }
+        self.length -= to_read;
+        to_read
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -36,16 +133,294 @@ pub struct SendParam {
     pub next: u32,        // SND.NXT
     pub window: u16,      // window size
     pub initial_seq: u32, // 初期受信seq
+    pub rtt: RttEstimator,
+    /// ハンドシェイクで交渉した送信 MSS（ピアが広告した上限）。
+    pub mss: u16,
+    /// 輻輳ウィンドウ（バイト）。
+    pub cwnd: u32,
+    /// スロースタート閾値（バイト）。
+    pub ssthresh: u32,
+    /// 連続した重複 ACK の数。3 つで fast retransmit を起こす。
+    pub dup_ack_count: u8,
+}
+
+/// TCP オプションの kind。
+mod option_kind {
+    pub const END: u8 = 0;
+    pub const NOP: u8 = 1;
+    pub const MSS: u8 = 2;
+}
+
+/// SYN に付けない場合のデフォルト MSS（イーサネット上の一般的な値）。
+pub const DEFAULT_MSS: u16 = 1460;
+
+/// MSS オプションのバイト列 (kind=2, len=4, value) を返す。
+pub fn build_mss_option(mss: u16) -> Vec<u8> {
+    let mut option = vec![option_kind::MSS, 4];
+    option.extend_from_slice(&mss.to_be_bytes());
+    option
+}
+
+/// TCP オプション領域を解釈し、広告された MSS があれば返す。
+/// End-of-Option-List で打ち切り、No-Operation はパディングとして読み飛ばす。
+pub fn parse_mss_option(options: &[u8]) -> Option<u16> {
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            option_kind::END => break,
+            option_kind::NOP => i += 1,
+            option_kind::MSS => {
+                // kind, length, value(2) の計 4 バイト。
+                if i + 4 <= options.len() && options[i + 1] == 4 {
+                    return Some(u16::from_be_bytes([options[i + 2], options[i + 3]]));
+                }
+                // 壊れた MSS オプションは length に従って読み飛ばし、後続を諦めない。
+                let len = if i + 1 < options.len() {
+                    options[i + 1] as usize
+                } else {
+                    break;
+                };
+                if len < 2 {
+                    break;
+                }
+                i += len;
+            }
+            _ => {
+                // それ以外の可変長オプションは length に従って読み飛ばす。
+                if i + 1 >= options.len() {
+                    break;
+                }
+                let len = options[i + 1] as usize;
+                if len < 2 {
+                    break;
+                }
+                i += len;
+            }
+        }
+    }
+    None
+}
+
+impl SendParam {
+    /// RFC 793 の ACK 受理条件: `SND.UNA < SEG.ACK <= SND.NXT`。
+    /// シーケンス空間の折り返しを考慮した比較を行う。
+    pub fn is_valid_ack(&self, ack: u32) -> bool {
+        // (SEG.ACK - SND.UNA) と (SND.NXT - SND.UNA) の差で半開区間を判定する。
+        let ack_behind = ack.wrapping_sub(self.unacked_seq);
+        let window = self.next.wrapping_sub(self.unacked_seq);
+        ack_behind != 0 && ack_behind <= window
+    }
+
+    /// `SEG.ACK` がまだ送っていないデータを ACK している（`SEG.ACK > SND.NXT`）か。
+    pub fn acks_unsent_data(&self, ack: u32) -> bool {
+        // SND.UNA より古い ACK と区別するため、SND.NXT との符号付き差で判定する。
+        (ack.wrapping_sub(self.next) as i32) > 0
+    }
+
+    /// 送出可能ウィンドウ。フロー制御（ピア広告）と輻輳制御（cwnd）の小さい方。
+    pub fn usable_window(&self) -> u32 {
+        self.cwnd.min(self.window as u32)
+    }
+
+    /// 新しいデータを ACK されたとき（重複でない ACK）に cwnd を育てる。
+    /// ssthresh 未満ならスロースタート（ACK ごとに +MSS）、以上なら輻輳回避
+    /// （RTT ごとにおよそ +MSS、すなわち ACK ごとに +MSS*MSS/cwnd）。
+    pub fn on_ack(&mut self) {
+        self.dup_ack_count = 0;
+        let mss = self.mss as u32;
+        if self.cwnd < self.ssthresh {
+            self.cwnd += mss;
+        } else {
+            self.cwnd += (mss * mss / self.cwnd).max(1);
+        }
+    }
+
+    /// 再送タイムアウトによるロスト検出時。ssthresh を flight の半分（下限 2*MSS）に
+    /// 落とし、cwnd を 1 MSS に戻してスロースタートからやり直す。
+    pub fn on_timeout(&mut self, flight: u32) {
+        let mss = self.mss as u32;
+        self.ssthresh = (flight / 2).max(2 * mss);
+        self.cwnd = mss;
+        self.dup_ack_count = 0;
+    }
+
+    /// 重複 ACK を受けた。3 つ目で fast retransmit とみなし、cwnd を半減して
+    /// true を返す（呼び出し側が即時再送する）。
+    pub fn on_duplicate_ack(&mut self, flight: u32) -> bool {
+        self.dup_ack_count += 1;
+        if self.dup_ack_count == 3 {
+            let mss = self.mss as u32;
+            self.ssthresh = (flight / 2).max(2 * mss);
+            self.cwnd = self.ssthresh;
+            return true;
+        }
+        false
+    }
 }
 
-type P = (u32, u32);
+/// 再送最大回数。これを超えたら接続を諦める。
+pub const MAX_TRANSMISSION: u8 = 5;
+
+/// Jacobson/Karn アルゴリズムによる RTO 推定。
+/// `srtt` は平滑化 RTT、`rttvar` は RTT の分散、単位は秒。
+#[derive(Clone, Debug)]
+pub struct RttEstimator {
+    srtt: f64,
+    rttvar: f64,
+    rto: Duration,
+    has_sample: bool,
+}
+
+impl RttEstimator {
+    /// RTO の下限・上限。
+    const MIN_RTO: Duration = Duration::from_secs(1);
+    const MAX_RTO: Duration = Duration::from_secs(60);
+
+    pub fn new() -> Self {
+        Self {
+            srtt: 0.0,
+            rttvar: 0.0,
+            rto: Self::MIN_RTO,
+            has_sample: false,
+        }
+    }
+
+    /// 現在の再送タイムアウト。
+    pub fn rto(&self) -> Duration {
+        self.rto
+    }
+
+    /// RTT サンプル `sample` を取り込み、`srtt`/`rttvar`/`rto` を更新する。
+    /// Karn のアルゴリズムにより、再送したセグメントからはサンプルを取らないこと。
+    pub fn update(&mut self, sample: Duration) {
+        let r = sample.as_secs_f64();
+        if !self.has_sample {
+            self.srtt = r;
+            self.rttvar = r / 2.0;
+            self.has_sample = true;
+        } else {
+            self.rttvar = 0.75 * self.rttvar + 0.25 * (self.srtt - r).abs();
+            self.srtt = 0.875 * self.srtt + 0.125 * r;
+        }
+        let rto = Duration::from_secs_f64(self.srtt + 4.0 * self.rttvar);
+        self.rto = rto.clamp(Self::MIN_RTO, Self::MAX_RTO);
+    }
+}
+
+impl Default for RttEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct RecvParam {
     pub next: u32,        // RCV.NXT
     pub window: u16,      // window size
     pub initial_seq: u32, // 初期受信seq
-    pub tails: BinaryHeap<Reverse<P>>,
+    pub assembler: Assembler,
+}
+
+/// `RCV.NXT` より先に届いたデータを、先頭シーケンス番号付きの断片として保持する。
+/// ロストした前任セグメントより先に届いたバイトをここに退避しておき、隙間が埋まった
+/// 時点で `collapse` が連続分を取り出せるようにする。断片は先頭 seq で昇順・非隣接に保つ。
+///
+/// シーケンス番号は ISN がランダムなため 32bit 空間で折り返す。比較・距離計算は
+/// すべて `wrapping_sub` による相対オフセットで行い、オーバーフローを避ける。
+#[derive(Clone, Debug, Default)]
+pub struct Assembler {
+    segments: Vec<(u32, Vec<u8>)>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self {
+            segments: Vec::new(),
+        }
+    }
+
+    /// まだ埋まっていない隙間が残っているか。socket が即時 ACK を判断するのに使う。
+    pub fn has_gaps(&self) -> bool {
+        !self.segments.is_empty()
+    }
+
+    /// `RCV.NXT` より先行して届いた断片 `data`（先頭 seq）を退避する。隣接/重複する
+    /// 既存断片は併合し、`next` より手前のバイトは捨てる。`next` 自体は進めない。
+    pub fn insert(&mut self, next: u32, seq: u32, data: &[u8]) {
+        // next より手前のバイトは受理済みなので切り詰める。相対距離が小さい方が
+        // 真の関係（ウィンドウ内に収まる）なので、それで前後を判定する。
+        let behind = next.wrapping_sub(seq);
+        // seq < next かは符号付き比較（(next - seq) を i32 として見る）で判定する。
+        let starts_before_next = (behind as i32) > 0;
+        let (data_start, data): (u32, &[u8]) = if starts_before_next {
+            if (behind as usize) >= data.len() {
+                return; // 全域が next 以下。既に受理済みなので破棄。
+            }
+            (next, &data[behind as usize..]) // 跨ぎ。手前の分を切り詰める。
+        } else {
+            (seq, data)
+        };
+        if data.is_empty() {
+            return;
+        }
+        // union の範囲。併合により手前/後ろへ広がりうる。
+        let mut start = data_start;
+        let mut end = data_start.wrapping_add(data.len() as u32);
+        // next 基準の相対オフセットで順序・隣接を判定する。
+        let rel = |x: u32| x.wrapping_sub(next);
+
+        // 対象範囲に重なる/隣接する既存断片を集めて union を作る。
+        let mut keep: Vec<(u32, Vec<u8>)> = Vec::with_capacity(self.segments.len() + 1);
+        let mut overlapping: Vec<(u32, Vec<u8>)> = Vec::new();
+        for (seg_start, bytes) in std::mem::take(&mut self.segments) {
+            let seg_end = seg_start.wrapping_add(bytes.len() as u32);
+            if rel(seg_end) < rel(start) || rel(seg_start) > rel(end) {
+                keep.push((seg_start, bytes)); // 非隣接（手前 or 後ろ）
+            } else {
+                // 重なる or 隣接。union 範囲を広げて後でまとめる。
+                if rel(seg_start) < rel(start) {
+                    start = seg_start;
+                }
+                if rel(seg_end) > rel(end) {
+                    end = seg_end;
+                }
+                overlapping.push((seg_start, bytes));
+            }
+        }
+
+        // union バイト列を組み立てる。既存断片を先に、新しい data を後に書いて上書きする。
+        let mut buf = vec![0u8; end.wrapping_sub(start) as usize];
+        for (seg_start, bytes) in overlapping {
+            let at = seg_start.wrapping_sub(start) as usize;
+            buf[at..at + bytes.len()].copy_from_slice(&bytes);
+        }
+        let data_at = data_start.wrapping_sub(start) as usize;
+        buf[data_at..data_at + data.len()].copy_from_slice(data);
+
+        keep.push((start, buf));
+        keep.sort_by_key(|(s, _)| s.wrapping_sub(next));
+        self.segments = keep;
+    }
+
+    /// `next` に連続する断片を先頭から取り出し、新しい `RCV.NXT` と取り出した
+    /// バイト列（recv バッファへ enqueue する分）を返す。`next` 以前に始まる断片は
+    /// `next` を越える分だけを取り出し、全域が `next` 以下の断片は捨てる。
+    pub fn collapse(&mut self, mut next: u32) -> (u32, Vec<u8>) {
+        let mut out = Vec::new();
+        while let Some(pos) = self
+            .segments
+            .iter()
+            .position(|(s, _)| (next.wrapping_sub(*s) as i32) >= 0)
+        {
+            let (start, bytes) = self.segments.remove(pos);
+            let skip = next.wrapping_sub(start) as usize;
+            if skip < bytes.len() {
+                next = next.wrapping_add((bytes.len() - skip) as u32);
+                out.extend_from_slice(&bytes[skip..]);
+            }
+        }
+        (next, out)
+    }
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
@@ -56,9 +431,11 @@ pub enum TcpStatus {
     Established,
     FinWait1,
     FinWait2,
+    Closing,
     TimeWait,
     CloseWait,
     LastAck,
+    Closed,
 }
 
 impl Display for TcpStatus {
@@ -70,9 +447,11 @@ impl Display for TcpStatus {
             TcpStatus::Established => write!(f, "ESTABLISHED"),
             TcpStatus::FinWait1 => write!(f, "FINWAIT1"),
             TcpStatus::FinWait2 => write!(f, "FINWAIT2"),
+            TcpStatus::Closing => write!(f, "CLOSING"),
             TcpStatus::TimeWait => write!(f, "TIMEWAIT"),
             TcpStatus::CloseWait => write!(f, "CLOSEWAIT"),
             TcpStatus::LastAck => write!(f, "LASTACK"),
+            TcpStatus::Closed => write!(f, "CLOSED"),
         }
     }
 }
@@ -100,19 +479,27 @@ impl Socket {
                 next: 0,
                 window: SOCKET_BUFFER_SIZE as u16,
                 initial_seq: 0,
+                rtt: RttEstimator::new(),
+                mss: DEFAULT_MSS,
+                // 初期 cwnd は MSS の小さな倍数、ssthresh は広告ウィンドウから始める。
+                cwnd: DEFAULT_MSS as u32 * 2,
+                ssthresh: SOCKET_BUFFER_SIZE as u32,
+                dup_ack_count: 0,
             },
+            send_buffer: SocketBuffer::new(SOCKET_BUFFER_SIZE),
             recv_param: RecvParam {
                 next: 0,
                 window: SOCKET_BUFFER_SIZE as u16,
                 initial_seq: 0,
-                tails: BinaryHeap::new(),
+                assembler: Assembler::new(),
             },
             status,
             connected_connection_queue: VecDeque::new(),
             listening_socket: None,
             sender,
-            recv_buffer: vec![0; SOCKET_BUFFER_SIZE],
+            recv_buffer: SocketBuffer::new(SOCKET_BUFFER_SIZE),
             retransmission_queue: VecDeque::new(),
+            keep_alive: KeepAlive::default(),
         })
     }
 
@@ -123,15 +510,27 @@ impl Socket {
         flag: u8,
         payload: &[u8],
     ) -> Result<usize> {
-        let mut tcp_packet = TCPPacket::new(payload.len());
+        // SYN / SYN-ACK には MSS オプションを載せる。それ以外はオプション無し。
+        // 広告値は MTU に収まるセグメントサイズであって受信バッファ容量ではない。
+        let options = if flag & tcpflags::SYN != 0 {
+            let mss = DEFAULT_MSS.min(self.recv_buffer.capacity().min(u16::MAX as usize) as u16);
+            build_mss_option(mss)
+        } else {
+            Vec::new()
+        };
+        let mut tcp_packet = TCPPacket::new(options.len() + payload.len());
         tcp_packet.set_src(self.local_port);
         tcp_packet.set_dest(self.remote_port);
         tcp_packet.set_seq(seq);
         tcp_packet.set_ack(ack);
-        // オプションフィールドを使わないので固定
-        tcp_packet.set_data_offset(5);
+        if !options.is_empty() {
+            tcp_packet.set_options(&options);
+        }
+        // data offset は実際のオプション長（4 バイト境界）から計算する。
+        tcp_packet.set_data_offset(5 + (options.len() / 4) as u8);
         tcp_packet.set_flag(flag);
-        tcp_packet.set_window_size(self.recv_param.window);
+        // 実際の空き容量を広告することで、消費者の進捗がフロー制御に反映される。
+        tcp_packet.set_window_size(self.recv_buffer.window() as u16);
         tcp_packet.set_payload(payload);
         tcp_packet.set_checksum(util::ipv4_checksum(
             &tcp_packet.packet(),
@@ -156,6 +555,54 @@ impl Socket {
         Ok(sent_size)
     }
 
+    /// アプリケーションからの送信データを送信リングバッファに積む。
+    /// 実際の送出は `flush_send_buffer` がウィンドウの許す範囲で行う。
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        self.send_buffer.enqueue_slice(data)
+    }
+
+    /// 送信バッファのデータを、フロー制御・輻輳制御・MSS に従ってセグメント化して送出する。
+    /// 送出可能ウィンドウは `min(cwnd, peer_window)` から in-flight 量を引いた残り。
+    pub fn flush_send_buffer(&mut self) -> Result<()> {
+        let mss = self.send_param.mss as usize;
+        while !self.send_buffer.is_empty() {
+            let in_flight = self
+                .send_param
+                .next
+                .wrapping_sub(self.send_param.unacked_seq) as usize;
+            let usable = (self.send_param.usable_window() as usize).saturating_sub(in_flight);
+            if usable == 0 {
+                break; // ウィンドウが閉じている
+            }
+            let chunk = usable.min(mss).min(self.send_buffer.len());
+            let mut payload = vec![0u8; chunk];
+            let n = self.send_buffer.dequeue_slice(&mut payload);
+            payload.truncate(n);
+            let seq = self.send_param.next;
+            let ack = self.recv_param.next;
+            self.send_tcp_packet(seq, ack, tcpflags::ACK, &payload)?;
+            self.send_param.next = self.send_param.next.wrapping_add(n as u32);
+        }
+        Ok(())
+    }
+
+    /// keep-alive を有効にし、アイドル判定間隔を設定する。
+    pub fn set_keep_alive(&mut self, interval: Duration) {
+        self.keep_alive.interval = Some(interval);
+        self.keep_alive.refresh_deadline();
+    }
+
+    /// keep-alive プローブ（`seq = SND.NXT - 1` の空セグメント）を送る。
+    /// 期限を張り直し、送信済みプローブ数を進める。
+    pub fn send_keepalive_probe(&mut self) -> Result<usize> {
+        let seq = self.send_param.next.wrapping_sub(1);
+        let ack = self.recv_param.next;
+        let sent = self.send_tcp_packet(seq, ack, tcpflags::ACK, &[])?;
+        self.keep_alive.probes_sent += 1;
+        self.keep_alive.refresh_deadline();
+        Ok(sent)
+    }
+
     pub fn get_sock_id(&self) -> SockID {
         SockID(
             self.local_addr,
@@ -166,11 +613,62 @@ impl Socket {
     }
 }
 
+/// マッチする `SockID` もリスニングソケットも無い 4-tuple 宛のセグメントに対し、
+/// 確立済み `Socket` を用意せず RST を返すためのヘルパ。
+///
+/// 入力セグメントの内容に応じて RFC 793 の通り seq/ack を決める:
+/// ACK があれば `seq = SEG.ACK` で bare RST、無ければ `ack = SEG.SEQ + SEG.LEN`
+/// として RST|ACK を送る。
+pub fn send_rst(
+    local_addr: Ipv4Addr,
+    remote_addr: Ipv4Addr,
+    local_port: u16,
+    remote_port: u16,
+    seg_seq: u32,
+    seg_ack: u32,
+    seg_len: u32,
+    seg_has_ack: bool,
+) -> Result<usize> {
+    let (seq, ack, flag) = if seg_has_ack {
+        (seg_ack, 0, tcpflags::RST)
+    } else {
+        (0, seg_seq.wrapping_add(seg_len), tcpflags::RST | tcpflags::ACK)
+    };
+
+    let (mut sender, _) = transport::transport_channel(
+        65535,
+        TransportChannelType::Layer4(TransportProtocol::Ipv4(IpNextHeaderProtocols::Tcp)),
+    )?;
+
+    let mut tcp_packet = TCPPacket::new(0);
+    tcp_packet.set_src(local_port);
+    tcp_packet.set_dest(remote_port);
+    tcp_packet.set_seq(seq);
+    tcp_packet.set_ack(ack);
+    tcp_packet.set_data_offset(5);
+    tcp_packet.set_flag(flag);
+    tcp_packet.set_window_size(0);
+    tcp_packet.set_checksum(util::ipv4_checksum(
+        &tcp_packet.packet(),
+        8,
+        &[],
+        &local_addr,
+        &remote_addr,
+        IpNextHeaderProtocols::Tcp,
+    ));
+    let sent_size = sender
+        .send_to(tcp_packet.clone(), IpAddr::V4(remote_addr))
+        .context(format!("failed to send rst: \n{:?}", tcp_packet))?;
+    Ok(sent_size)
+}
+
 #[derive(Clone, Debug)]
 pub struct RetransmissionQueueEntry {
     pub packet: TCPPacket,
     pub latest_transmission_time: SystemTime,
     pub transmission_count: u8,
+    /// Karn のアルゴリズム用。再送したセグメントは RTT サンプルに使わない。
+    pub retransmitted: bool,
 }
 
 impl RetransmissionQueueEntry {
@@ -179,6 +677,28 @@ impl RetransmissionQueueEntry {
             packet,
             latest_transmission_time: SystemTime::now(),
             transmission_count: 1,
+            retransmitted: false,
         }
     }
+
+    /// 現在の `rto` に対し、再送回数に応じた指数バックオフを掛けた実効タイムアウト。
+    pub fn effective_rto(&self, rto: Duration) -> Duration {
+        let backoff = 1u32 << (self.transmission_count.saturating_sub(1)).min(6);
+        rto.saturating_mul(backoff)
+    }
+
+    /// 実効 RTO を過ぎていれば再送すべき。
+    pub fn is_timed_out(&self, rto: Duration) -> bool {
+        self.latest_transmission_time
+            .elapsed()
+            .map(|elapsed| elapsed >= self.effective_rto(rto))
+            .unwrap_or(false)
+    }
+
+    /// 再送時に呼ぶ。送信時刻と回数を更新し、Karn 用フラグを立てる。
+    pub fn mark_retransmitted(&mut self) {
+        self.latest_transmission_time = SystemTime::now();
+        self.transmission_count = self.transmission_count.saturating_add(1);
+        self.retransmitted = true;
+    }
 }